@@ -0,0 +1,253 @@
+use std::io::{ Result, Error, ErrorKind };
+use std::marker::PhantomData;
+
+use ply::*;
+
+/// Turns a parsed `DefaultElement` back into a user type.
+///
+/// Counterpart to `ToElement`: the proxy types `Scalar`, `ListOf`, `OneOf` and
+/// `Record` are composed instead of implementing decoding on the output types.
+pub trait Decoder {
+    /// The value this decoder produces on success.
+    type Output;
+    /// Decode `element`; `def` is the definition it was parsed against. The
+    /// built-in decoders do not read `def`, and their errors name only the property.
+    fn decode(&self, element: &DefaultElement, def: &ElementDef) -> Result<Self::Output>;
+}
+
+/// Name of the `Property` variant, used to build precise type-mismatch errors.
+fn property_kind(property: &Property) -> &'static str {
+    match *property {
+        Property::Char(_) => "char",
+        Property::UChar(_) => "uchar",
+        Property::Short(_) => "short",
+        Property::UShort(_) => "ushort",
+        Property::Int(_) => "int",
+        Property::UInt(_) => "uint",
+        Property::Float(_) => "float",
+        Property::Double(_) => "double",
+        Property::ListChar(_) => "list char",
+        Property::ListUChar(_) => "list uchar",
+        Property::ListShort(_) => "list short",
+        Property::ListUShort(_) => "list ushort",
+        Property::ListInt(_) => "list int",
+        Property::ListUInt(_) => "list uint",
+        Property::ListFloat(_) => "list float",
+        Property::ListDouble(_) => "list double",
+    }
+}
+
+fn missing(key: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("missing property {}", key))
+}
+
+fn mismatch(expected: &str, key: &str, found: &Property) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("expected {} at property {}, found {}", expected, key, property_kind(found)),
+    )
+}
+
+/// Decodes a single scalar property named `key` into `T`.
+///
+/// Construct with `Scalar::new("x")`; the target type is chosen by the `Decoder`
+/// impl selected at the call site, e.g. `Scalar::<f32>::new("x")`.
+pub struct Scalar<T> {
+    key: String,
+    _marker: PhantomData<T>,
+}
+impl<T> Scalar<T> {
+    pub fn new<S: Into<String>>(key: S) -> Self {
+        Scalar { key: key.into(), _marker: PhantomData }
+    }
+}
+
+/// Decodes a list property named `key` into `Vec<T>`.
+pub struct ListOf<T> {
+    key: String,
+    _marker: PhantomData<T>,
+}
+impl<T> ListOf<T> {
+    pub fn new<S: Into<String>>(key: S) -> Self {
+        ListOf { key: key.into(), _marker: PhantomData }
+    }
+}
+
+/// A decoded property value, type-tagged so field decoders of different
+/// `Output` types can share one `Record`. The variants mirror `Property`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Char(i8),
+    UChar(u8),
+    Short(i16),
+    UShort(u16),
+    Int(i32),
+    UInt(u32),
+    Float(f32),
+    Double(f64),
+    ListChar(Vec<i8>),
+    ListUChar(Vec<u8>),
+    ListShort(Vec<i16>),
+    ListUShort(Vec<u16>),
+    ListInt(Vec<i32>),
+    ListUInt(Vec<u32>),
+    ListFloat(Vec<f32>),
+    ListDouble(Vec<f64>),
+}
+
+/// Lifts a concrete decoder output into the type-erased [`Value`], so that
+/// sub-decoders of different output types can share one `Record`.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+macro_rules! into_value(
+    ($t:ty, $variant:ident) => (
+        impl IntoValue for $t {
+            fn into_value(self) -> Value { Value::$variant(self) }
+        }
+    )
+);
+into_value!(i8, Char);
+into_value!(u8, UChar);
+into_value!(i16, Short);
+into_value!(u16, UShort);
+into_value!(i32, Int);
+into_value!(u32, UInt);
+into_value!(f32, Float);
+into_value!(f64, Double);
+into_value!(Vec<i8>, ListChar);
+into_value!(Vec<u8>, ListUChar);
+into_value!(Vec<i16>, ListShort);
+into_value!(Vec<u16>, ListUShort);
+into_value!(Vec<i32>, ListInt);
+into_value!(Vec<u32>, ListUInt);
+into_value!(Vec<f32>, ListFloat);
+into_value!(Vec<f64>, ListDouble);
+
+/// Erases a typed decoder's output to [`Value`] so it can be boxed alongside
+/// decoders of other output types.
+struct Erased<D>(D);
+impl<D> Decoder for Erased<D> where D: Decoder, D::Output: IntoValue {
+    type Output = Value;
+    fn decode(&self, element: &DefaultElement, def: &ElementDef) -> Result<Value> {
+        Ok(try!(self.0.decode(element, def)).into_value())
+    }
+}
+
+/// Boxes a typed decoder as an erased alternative for use with `OneOf`.
+pub fn boxed<D>(decoder: D) -> Box<Decoder<Output = Value>>
+    where D: Decoder + 'static, D::Output: IntoValue {
+    Box::new(Erased(decoder))
+}
+
+/// Tries each alternative in turn and yields the first that succeeds, so a value
+/// is accepted when it matches any one of the supplied decoders. The alternatives
+/// may have different output types, since each is erased to [`Value`].
+///
+/// Build one directly as `OneOf(vec![boxed(a), boxed(b)])`, or via `OneOf::new()`
+/// with `.or(..)`. On total failure the last candidate's error is surfaced, so it
+/// names only the last property tried.
+pub struct OneOf(pub Vec<Box<Decoder<Output = Value>>>);
+
+impl OneOf {
+    pub fn new() -> Self {
+        OneOf(Vec::new())
+    }
+    /// Adds an alternative sub-decoder of any output type.
+    pub fn or<D>(mut self, decoder: D) -> Self where D: Decoder + 'static, D::Output: IntoValue {
+        self.0.push(boxed(decoder));
+        self
+    }
+}
+
+impl Decoder for OneOf {
+    type Output = Value;
+    fn decode(&self, element: &DefaultElement, def: &ElementDef) -> Result<Value> {
+        let mut last = Error::new(ErrorKind::InvalidData, "OneOf: no alternatives supplied");
+        for candidate in &self.0 {
+            match candidate.decode(element, def) {
+                Ok(v) => return Ok(v),
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+}
+
+/// Runs several named sub-decoders and collects their outputs in order. The
+/// sub-decoders may have different output types (e.g. a `Scalar::<f32>` next to a
+/// `ListOf::<u32>`), each erased to [`Value`], so one `Record` maps a whole PLY
+/// element into the fields of a user geometry type.
+pub struct Record {
+    fields: Vec<(String, Box<Decoder<Output = Value>>)>,
+}
+impl Record {
+    pub fn new() -> Self {
+        Record { fields: Vec::new() }
+    }
+    /// Adds a named sub-decoder of any output type; the name labels its value.
+    pub fn field<S: Into<String>, D>(mut self, name: S, decoder: D) -> Self
+        where D: Decoder + 'static, D::Output: IntoValue {
+        self.fields.push((name.into(), boxed(decoder)));
+        self
+    }
+}
+impl Decoder for Record {
+    type Output = Vec<(String, Value)>;
+    fn decode(&self, element: &DefaultElement, def: &ElementDef) -> Result<Self::Output> {
+        let mut out = Vec::with_capacity(self.fields.len());
+        for &(ref name, ref decoder) in &self.fields {
+            out.push((name.clone(), try!(decoder.decode(element, def))));
+        }
+        Ok(out)
+    }
+}
+
+macro_rules! scalar_decoder(
+    ($t:ty, $variant:ident, $name:expr) => (
+        impl Decoder for Scalar<$t> {
+            type Output = $t;
+            fn decode(&self, element: &DefaultElement, _def: &ElementDef) -> Result<$t> {
+                match element.get(&self.key) {
+                    None => Err(missing(&self.key)),
+                    Some(&Property::$variant(v)) => Ok(v),
+                    Some(other) => Err(mismatch($name, &self.key, other)),
+                }
+            }
+        }
+    )
+);
+
+macro_rules! list_decoder(
+    ($t:ty, $variant:ident, $name:expr) => (
+        impl Decoder for ListOf<$t> {
+            type Output = Vec<$t>;
+            fn decode(&self, element: &DefaultElement, _def: &ElementDef) -> Result<Vec<$t>> {
+                match element.get(&self.key) {
+                    None => Err(missing(&self.key)),
+                    Some(&Property::$variant(ref v)) => Ok(v.clone()),
+                    Some(other) => Err(mismatch($name, &self.key, other)),
+                }
+            }
+        }
+    )
+);
+
+scalar_decoder!(i8, Char, "char");
+scalar_decoder!(u8, UChar, "uchar");
+scalar_decoder!(i16, Short, "short");
+scalar_decoder!(u16, UShort, "ushort");
+scalar_decoder!(i32, Int, "int");
+scalar_decoder!(u32, UInt, "uint");
+scalar_decoder!(f32, Float, "float");
+scalar_decoder!(f64, Double, "double");
+
+list_decoder!(i8, ListChar, "list char");
+list_decoder!(u8, ListUChar, "list uchar");
+list_decoder!(i16, ListShort, "list short");
+list_decoder!(u16, ListUShort, "list ushort");
+list_decoder!(i32, ListInt, "list int");
+list_decoder!(u32, ListUInt, "list uint");
+list_decoder!(f32, ListFloat, "list float");
+list_decoder!(f64, ListDouble, "list double");
@@ -0,0 +1,4 @@
+extern crate byteorder;
+
+pub mod writer;
+pub mod decoder;
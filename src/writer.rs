@@ -1,10 +1,72 @@
-use std::io::{ Write, Result, Error, ErrorKind };
+use std::io::{ self, Write };
 use std::string::ToString;
+use std::error;
+use std::fmt;
 
 use byteorder::{ BigEndian, LittleEndian, WriteBytesExt, ByteOrder };
 
 use ply::*;
 
+/// Everything that can go wrong while writing a PLY stream.
+#[derive(Debug)]
+pub enum WriteError {
+    /// An element was missing a property declared in its `ElementDef`.
+    MissingProperty { element: String, property: String },
+    /// A list was longer than its declared index type can represent.
+    ListIndexOverflow,
+    /// A list was declared with a non-integer (float/double) index type.
+    InvalidListIndexType,
+    /// The underlying writer failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            WriteError::MissingProperty { ref element, ref property } =>
+                write!(f, "element `{}` is missing the declared property `{}`", element, property),
+            WriteError::ListIndexOverflow =>
+                write!(f, "list length does not fit in the declared list index type"),
+            WriteError::InvalidListIndexType =>
+                write!(f, "list index must be an integer type, float/double declared in PropertyType"),
+            WriteError::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for WriteError {
+    fn description(&self) -> &str {
+        match *self {
+            WriteError::MissingProperty { .. } => "missing property",
+            WriteError::ListIndexOverflow => "list index overflow",
+            WriteError::InvalidListIndexType => "invalid list index type",
+            WriteError::Io(ref e) => e.description(),
+        }
+    }
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            WriteError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WriteError {
+    fn from(e: io::Error) -> WriteError {
+        WriteError::Io(e)
+    }
+}
+
+/// Result of every writing operation in this module.
+pub type Result<T> = ::std::result::Result<T, WriteError>;
+
+/// Writes the whole slice and reports the byte count, since `Write::write` may
+/// perform a partial write whose returned count must not be ignored.
+fn write_all_bytes<T: Write + ?Sized>(out: &mut T, bytes: &[u8]) -> Result<usize> {
+    try!(out.write_all(bytes));
+    Ok(bytes.len())
+}
+
 pub enum NewLine {
     N,
     R,
@@ -29,11 +91,6 @@ pub struct Writer<P: ToElement<P> + PropertyAccess> {
     new_line: String,
     phantom: PhantomData<P>,
 }
-macro_rules! get_prop(
-    // TODO: errror
-    ($e:expr) => (match $e {None => return Ok(17), Some(x) => x})
-);
-
 impl<P: ToElement<P> + PropertyAccess> Writer<P> {
     pub fn new() -> Self {
         Writer {
@@ -54,47 +111,47 @@ impl<P: ToElement<P> + PropertyAccess> Writer<P> {
         let mut written = 0;
         written += try!(self.write_header(out, &ply.header));
         written += try!(self.write_payload(out, &ply.payload, &ply.header));
-        out.flush().unwrap();
+        try!(out.flush());
         Ok(written)
     }
     pub fn write_line_magic_number<T: Write>(&self, out: &mut T) -> Result<usize> {
         let mut written = 0;
-        written += try!(out.write("ply".as_bytes()));
+        written += try!(write_all_bytes(out, "ply".as_bytes()));
         written += try!(self.write_new_line(out));
         Ok(written)
     }
     pub fn write_line_format<T: Write>(&self, out: &mut T, encoding: &Encoding, version: &Version) -> Result<usize> {
         let mut written = 0;
-        written += try!(out.write("format ".as_bytes()));
+        written += try!(write_all_bytes(out, "format ".as_bytes()));
         written += try!(self.write_encoding(out, encoding));
-        written += try!(out.write(format!(" {}.{}", version.major, version.minor).as_bytes()));
+        written += try!(write_all_bytes(out, format!(" {}.{}", version.major, version.minor).as_bytes()));
         written += try!(self.write_new_line(out));
         Ok(written)
     }
     pub fn write_line_comment<T: Write>(&self, out: &mut T, comment: &Comment) -> Result<usize> {
         let mut written = 0;
-        written += try!(out.write(format!("comment {}", comment).as_bytes()));
+        written += try!(write_all_bytes(out, format!("comment {}", comment).as_bytes()));
         written += try!(self.write_new_line(out));
         Ok(written)
     }
     pub fn write_line_obj_info<T: Write>(&self, out: &mut T, obj_info: &ObjInfo) -> Result<usize> {
         let mut written = 0;
-        written += try!(out.write(format!("obj_info {}", obj_info).as_bytes()));
+        written += try!(write_all_bytes(out, format!("obj_info {}", obj_info).as_bytes()));
         written += try!(self.write_new_line(out));
         Ok(written)
     }
     pub fn write_line_element_definition<T: Write>(&self, out: &mut T, element: &ElementDef) -> Result<usize> {
         let mut written = 0;
-        written += try!(out.write(format!("element {} {}", element.name, element.count).as_bytes()));
+        written += try!(write_all_bytes(out, format!("element {} {}", element.name, element.count).as_bytes()));
         written += try!(self.write_new_line(out));
         Ok(written)
     }
     pub fn write_line_property_definition<T: Write>(&self, out: &mut T, property: &PropertyDef) -> Result<usize> {
         let mut written = 0;
-        written += try!(out.write("property ".as_bytes()));
+        written += try!(write_all_bytes(out, "property ".as_bytes()));
         written += try!(self.write_property_type(out, &property.data_type));
-        written += try!(out.write(" ".as_bytes()));
-        written += try!(out.write(property.name.as_bytes()));
+        written += try!(write_all_bytes(out, " ".as_bytes()));
+        written += try!(write_all_bytes(out, property.name.as_bytes()));
         written += try!(self.write_new_line(out));
         Ok(written)
     }
@@ -109,7 +166,7 @@ impl<P: ToElement<P> + PropertyAccess> Writer<P> {
     }
     pub fn write_line_end_header<T: Write>(&mut self, out: &mut T) -> Result<usize> {
         let mut written = 0;
-        written += try!(out.write("end_header".as_bytes()));
+        written += try!(write_all_bytes(out, "end_header".as_bytes()));
         written += try!(self.write_new_line(out));
         Ok(written)
     }
@@ -136,20 +193,20 @@ impl<P: ToElement<P> + PropertyAccess> Writer<P> {
             Encoding::BinaryBigEndian => "binary_big_endian",
             Encoding::BinaryLittleEndian => "binary_little_endian",
         };
-        out.write(s.as_bytes())
+        write_all_bytes(out, s.as_bytes())
     }
     fn write_property_type<T: Write>(&self, out: &mut T, data_type: &PropertyType) -> Result<usize> {
         match *data_type {
             PropertyType::Scalar(ref scalar_type) => self.write_scalar_type(out, &scalar_type),
             PropertyType::List(ref index_type, ref content_type) => {
-                let mut written = try!(out.write("list ".as_bytes()));
+                let mut written = try!(write_all_bytes(out, "list ".as_bytes()));
                 match *index_type {
-                    ScalarType::Float => return Err(Error::new(ErrorKind::InvalidInput, "List index can not be of type float.")),
-                    ScalarType::Double => return Err(Error::new(ErrorKind::InvalidInput, "List index can not be of type double.")),
+                    ScalarType::Float => return Err(WriteError::InvalidListIndexType),
+                    ScalarType::Double => return Err(WriteError::InvalidListIndexType),
                     _ => (),
                 };
                 written += try!(self.write_scalar_type(out, &index_type));
-                written += try!(out.write(" ".as_bytes()));
+                written += try!(write_all_bytes(out, " ".as_bytes()));
                 written += try!(self.write_scalar_type(out, &content_type));
                 Ok(written)
             }
@@ -157,162 +214,429 @@ impl<P: ToElement<P> + PropertyAccess> Writer<P> {
     }
     fn write_scalar_type<T: Write>(&self, out: &mut T, scalar_type: &ScalarType) -> Result<usize> {
         match *scalar_type {
-            ScalarType::Char => out.write("char".as_bytes()),
-            ScalarType::UChar => out.write("uchar".as_bytes()),
-            ScalarType::Short => out.write("short".as_bytes()),
-            ScalarType::UShort => out.write("ushort".as_bytes()),
-            ScalarType::Int => out.write("int".as_bytes()),
-            ScalarType::UInt => out.write("uint".as_bytes()),
-            ScalarType::Float => out.write("float".as_bytes()),
-            ScalarType::Double => out.write("double".as_bytes()),
+            ScalarType::Char => write_all_bytes(out, "char".as_bytes()),
+            ScalarType::UChar => write_all_bytes(out, "uchar".as_bytes()),
+            ScalarType::Short => write_all_bytes(out, "short".as_bytes()),
+            ScalarType::UShort => write_all_bytes(out, "ushort".as_bytes()),
+            ScalarType::Int => write_all_bytes(out, "int".as_bytes()),
+            ScalarType::UInt => write_all_bytes(out, "uint".as_bytes()),
+            ScalarType::Float => write_all_bytes(out, "float".as_bytes()),
+            ScalarType::Double => write_all_bytes(out, "double".as_bytes()),
         }
     }
     ///// Payload
     pub fn write_payload<T: Write>(&mut self, out: &mut T, payload: &Payload<P>, header: &Header) -> Result<usize> {
+        let encoder = self.encoder_for(&header.encoding);
+        self.write_payload_with(out, payload, header, &*encoder)
+    }
+    /// Writes the whole payload with a caller-supplied encoder instead of the one
+    /// selected from `header.encoding`, letting a custom `ElementEncoder`
+    /// (JSON-lines, column-packed, ...) drive the full file without reimplementing
+    /// the payload loop.
+    pub fn write_payload_with<T: Write>(&mut self, out: &mut T, payload: &Payload<P>, header: &Header, encoder: &ElementEncoder) -> Result<usize> {
         let mut written = 0;
         let element_defs = &header.elements;
         for (k, element_list) in payload {
             let element_def = &element_defs[k];
-            written += try!(self.write_payload_of_element(out, element_list, element_def, header));
+            written += try!(self.write_payload_of_element(out, element_list, element_def, encoder));
         }
         Ok(written)
     }
-    pub fn write_payload_of_element<T: Write>(&mut self, out: &mut T, element_list: &Vec<P>, element_def: &ElementDef, header: &Header) -> Result<usize> {
+    pub fn write_payload_of_element<T: Write>(&mut self, out: &mut T, element_list: &Vec<P>, element_def: &ElementDef, encoder: &ElementEncoder) -> Result<usize> {
         let mut written = 0;
-        match header.encoding {
-            Encoding::Ascii => for e in element_list {
-                let raw_element = try!(e.to_element(element_def));
-                written += try!(self.__write_ascii_element(out, &raw_element));
-            },
-            Encoding::BinaryBigEndian => for element in element_list {
-                written += try!(self.__write_binary_element::<T, BigEndian>(out, element, &element_def));
-            },
-            Encoding::BinaryLittleEndian => for element in element_list {
-                written += try!(self.__write_binary_element::<T, LittleEndian>(out, element, &element_def));
-            }
+        for e in element_list {
+            let raw_element = try!(e.to_element(element_def));
+            written += try!(encoder.write_element(out, &raw_element, element_def));
         }
         Ok(written)
     }
-    pub fn write_ascii_element<T: Write>(&self, out: &mut T, element: &P, element_def: &ElementDef) -> Result<usize> {
+    /// Picks the built-in [`ElementEncoder`](trait.ElementEncoder.html) matching
+    /// `encoding`; the `set_newline` policy is handed to the ASCII encoder here so
+    /// it lives in one place.
+    fn encoder_for(&self, encoding: &Encoding) -> Box<ElementEncoder> {
+        match *encoding {
+            Encoding::Ascii => Box::new(AsciiEncoder::new(self.new_line.clone())),
+            Encoding::BinaryBigEndian => Box::new(BinaryEncoder::<BigEndian>::new()),
+            Encoding::BinaryLittleEndian => Box::new(BinaryEncoder::<LittleEndian>::new()),
+        }
+    }
+    /// Writes a single element using the supplied encoder, ignoring the header encoding.
+    pub fn write_element_with<T: Write>(&self, out: &mut T, element: &P, element_def: &ElementDef, encoder: &ElementEncoder) -> Result<usize> {
         let raw_element = try!(element.to_element(element_def));
-        self.__write_ascii_element(out, &raw_element)
+        encoder.write_element(out, &raw_element, element_def)
+    }
+    pub fn write_ascii_element<T: Write>(&self, out: &mut T, element: &P, element_def: &ElementDef) -> Result<usize> {
+        self.write_element_with(out, element, element_def, &AsciiEncoder::new(self.new_line.clone()))
     }
     pub fn write_big_endian_element<T: Write> (&self, out: &mut T, element: &P, element_def: &ElementDef) -> Result<usize> {
-        self.__write_binary_element::<T, BigEndian>(out, element, element_def)
+        self.write_element_with(out, element, element_def, &BinaryEncoder::<BigEndian>::new())
     }
     pub fn write_little_endian_element<T: Write> (&self, out: &mut T, element: &P, element_def: &ElementDef) -> Result<usize> {
-        self.__write_binary_element::<T, BigEndian>(out, element, element_def)
+        self.write_element_with(out, element, element_def, &BinaryEncoder::<LittleEndian>::new())
     }
 
-    // private payload
-    fn __write_binary_element<T: Write, B: ByteOrder>(&self, out: &mut T, element: &P, element_def: &ElementDef) -> Result<usize> {
-        let mut written = 0;
-        for (k, property_def) in &element_def.properties {
-            match property_def.data_type {
-                PropertyType::Scalar(ref scalar_type) => {
-                    written += match *scalar_type {
-                        ScalarType::Char => {try!(out.write_i8(get_prop!(element.get_char(k)))); 1},
-                        ScalarType::UChar => {try!(out.write_u8(get_prop!(element.get_uchar(k)))); 1},
-                        ScalarType::Short => {try!(out.write_i16::<B>(get_prop!(element.get_short(k)))); 2},
-                        ScalarType::UShort => {try!(out.write_u16::<B>(get_prop!(element.get_ushort(k)))); 2},
-                        ScalarType::Int => {try!(out.write_i32::<B>(get_prop!(element.get_int(k)))); 4},
-                        ScalarType::UInt => {try!(out.write_u32::<B>(get_prop!(element.get_uint(k)))); 4},
-                        ScalarType::Float => {try!(out.write_f32::<B>(get_prop!(element.get_float(k)))); 4},
-                        ScalarType::Double => {try!(out.write_f64::<B>(get_prop!(element.get_double(k)))); 8},
-                    };
-                },
-                PropertyType::List(ref index_type, ref scalar_type) => {
-                    let vec_len = element_def.count;
-                    written += match *index_type {
-                        ScalarType::Char => {try!(out.write_i8(vec_len as i8)); 1},
-                        ScalarType::UChar => {try!(out.write_u8(vec_len as u8)); 1},
-                        ScalarType::Short => {try!(out.write_i16::<B>(vec_len as i16)); 2},
-                        ScalarType::UShort => {try!(out.write_u16::<B>(vec_len as u16)); 2},
-                        ScalarType::Int => {try!(out.write_i32::<B>(vec_len as i32)); 4},
-                        ScalarType::UInt => {try!(out.write_u32::<B>(vec_len as u32)); 4},
-                        ScalarType::Float => return Err(Error::new(ErrorKind::InvalidInput, "Index of list must be an integer type, float declared in PropertyType.")),
-                        ScalarType::Double => return Err(Error::new(ErrorKind::InvalidInput, "Index of list must be an integer type, double declared in PropertyType.")),
-                    };
+    fn write_new_line<T: Write>(&self, out: &mut T) -> Result<usize> {
+        write_all_bytes(out, self.new_line.as_bytes())
+    }
 
-                    written += match *scalar_type {
-                        ScalarType::Char => try!(self.write_binary_list::<T, i8, B>(get_prop!(element.get_list_char(k)), out, &|o, x| {try!(o.write_i8(*x)); Ok(1)} )),
-                        ScalarType::UChar => try!(self.write_binary_list::<T, u8, B>(get_prop!(element.get_list_uchar(k)), out, &|o, x| {try!(o.write_u8(*x)); Ok(1)} )),
-                        ScalarType::Short => try!(self.write_binary_list::<T, i16, B>(get_prop!(element.get_list_short(k)), out, &|o, x| {try!(o.write_i16::<B>(*x)); Ok(2)} )),
-                        ScalarType::UShort => try!(self.write_binary_list::<T, u16, B>(get_prop!(element.get_list_ushort(k)), out, &|o, x| {try!(o.write_u16::<B>(*x)); Ok(2)} )),
-                        ScalarType::Int => try!(self.write_binary_list::<T, i32, B>(get_prop!(element.get_list_int(k)), out, &|o, x| {try!(o.write_i32::<B>(*x)); Ok(4)} )),
-                        ScalarType::UInt => try!(self.write_binary_list::<T, u32, B>(get_prop!(element.get_list_uint(k)), out, &|o, x| {try!(o.write_u32::<B>(*x)); Ok(4)} )),
-                        ScalarType::Float => try!(self.write_binary_list::<T, f32, B>(get_prop!(element.get_list_float(k)), out, &|o, x| {try!(o.write_f32::<B>(*x)); Ok(4)} )),
-                        ScalarType::Double => try!(self.write_binary_list::<T, f64, B>(get_prop!(element.get_list_double(k)), out, &|o, x| {try!(o.write_f64::<B>(*x)); Ok(8)} )),
-                    }
-                }
-            }
+    /// Opens a streaming [`ElementSink`](struct.ElementSink.html) for one element
+    /// group, so rows can be fed one at a time instead of materializing the whole
+    /// `Payload`. Call `write_header` first; the encoding is taken from `header`.
+    pub fn sink_for_element<'a, T: Write>(&self, out: &'a mut T, header: &Header, element_name: &str) -> Result<ElementSink<'a, T, P>> {
+        let element_def = match header.elements.get(element_name) {
+            Some(def) => def.clone(),
+            None => return Err(WriteError::Io(io::Error::new(io::ErrorKind::InvalidInput, format!("no element `{}` declared in header", element_name)))),
         };
+        Ok(ElementSink {
+            out: out,
+            encoder: self.encoder_for(&header.encoding),
+            element_def: element_def,
+            rows: 0,
+            bytes: 0,
+            finished: false,
+            phantom: PhantomData,
+        })
+    }
+}
+
+/// A streaming writer bound to a single element group.
+///
+/// Produced by [`Writer::sink_for_element`](struct.Writer.html#method.sink_for_element);
+/// each `push` encodes one row against the bound `ElementDef` and returns the number
+/// of bytes written, while the running total is available through `bytes_written`.
+/// Call [`finish`](struct.ElementSink.html#method.finish) once every row has been
+/// pushed to check the row count against the declared `element.count`; dropping the
+/// sink without finishing trips a `debug_assert!` on a wrong count.
+#[must_use]
+pub struct ElementSink<'a, T: Write + 'a, P: ToElement<P> + PropertyAccess> {
+    out: &'a mut T,
+    encoder: Box<ElementEncoder>,
+    element_def: ElementDef,
+    rows: usize,
+    bytes: usize,
+    finished: bool,
+    phantom: PhantomData<P>,
+}
+impl<'a, T: Write + 'a, P: ToElement<P> + PropertyAccess> ElementSink<'a, T, P> {
+    /// Encodes and writes a single row, returning the bytes written for it.
+    pub fn push(&mut self, element: &P) -> Result<usize> {
+        let raw_element = try!(element.to_element(&self.element_def));
+        let n = try!(self.encoder.write_element(self.out, &raw_element, &self.element_def));
+        self.rows += 1;
+        self.bytes += n;
+        Ok(n)
+    }
+    /// Total number of payload bytes written through this sink so far.
+    pub fn bytes_written(&self) -> usize {
+        self.bytes
+    }
+    /// Consumes the sink, verifying that the number of rows pushed matches the
+    /// declared `element.count`, and returns the total payload byte count.
+    ///
+    /// A mismatch is reported as an error rather than a panic, so an undercount or
+    /// overcount surfaces through the normal `Result` path like any other write
+    /// failure.
+    #[must_use]
+    pub fn finish(mut self) -> Result<usize> {
+        // Take over the count check from the `Drop` guard, reporting a mismatch as
+        // an error rather than a debug assertion.
+        self.finished = true;
+        if self.rows != self.element_def.count {
+            return Err(WriteError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "element `{}` declared {} rows but {} were pushed",
+                    self.element_def.name, self.element_def.count, self.rows
+                ),
+            )));
+        }
+        Ok(self.bytes)
+    }
+}
+impl<'a, T: Write + 'a, P: ToElement<P> + PropertyAccess> Drop for ElementSink<'a, T, P> {
+    /// Guards against a sink dropped without `finish` having written the declared
+    /// number of rows. The check is a `debug_assert!`: in debug builds a miscount
+    /// panics from `drop`, in release builds it is not checked.
+    fn drop(&mut self) {
+        debug_assert!(
+            self.finished || self.rows == self.element_def.count,
+            "ElementSink for element `{}` expected {} rows but {} were pushed",
+            self.element_def.name, self.element_def.count, self.rows
+        );
+    }
+}
+
+/// Writes the per-property payload of one element in a concrete encoding.
+///
+/// [`AsciiEncoder`](struct.AsciiEncoder.html) and
+/// [`BinaryEncoder`](struct.BinaryEncoder.html) cover the built-in encodings;
+/// implementing the trait adds a custom layout. The provided `write_element`
+/// drives the hooks in property order, so most impls only fill in
+/// `write_scalar`/`write_list`.
+pub trait ElementEncoder {
+    /// Emitted once before the first property of an element.
+    fn begin_element(&self, _out: &mut Write) -> Result<usize> {
+        Ok(0)
+    }
+    /// Emitted between two consecutive properties (e.g. the ASCII space).
+    fn separator(&self, _out: &mut Write) -> Result<usize> {
+        Ok(0)
+    }
+    /// Emitted once after the last property of an element (e.g. the ASCII newline).
+    fn end_element(&self, _out: &mut Write) -> Result<usize> {
+        Ok(0)
+    }
+    /// Writes a single scalar property of the declared `scalar_type`.
+    fn write_scalar(&self, out: &mut Write, scalar_type: &ScalarType, property: &Property) -> Result<usize>;
+    /// Writes a single list property with the declared index and content types.
+    fn write_list(&self, out: &mut Write, index_type: &ScalarType, content_type: &ScalarType, property: &Property, element_def: &ElementDef) -> Result<usize>;
+
+    /// Drives the hooks above over every property of `element` in definition order.
+    fn write_element(&self, out: &mut Write, element: &DefaultElement, element_def: &ElementDef) -> Result<usize> {
+        let mut written = try!(self.begin_element(out));
+        let mut first = true;
+        for (k, property_def) in &element_def.properties {
+            if !first {
+                written += try!(self.separator(out));
+            }
+            first = false;
+            let property = match element.get(k) {
+                Some(p) => p,
+                None => return Err(WriteError::MissingProperty {
+                    element: element_def.name.clone(),
+                    property: k.clone(),
+                }),
+            };
+            written += match property_def.data_type {
+                PropertyType::Scalar(ref scalar_type) => try!(self.write_scalar(out, scalar_type, property)),
+                PropertyType::List(ref index_type, ref content_type) => try!(self.write_list(out, index_type, content_type, property, element_def)),
+            };
+        }
+        written += try!(self.end_element(out));
         Ok(written)
     }
-    fn write_binary_list<T: Write, D, B: ByteOrder>(&self, list: &[D], out: &mut T, out_val: &Fn(&mut T, &D) -> Result<usize>) -> Result<usize> {
-        let mut written = 0;
+}
+
+/// Emits the whitespace-separated, newline-terminated `ascii` payload layout.
+///
+/// Note: properties are space-*separated*, so no trailing space precedes the
+/// newline.
+pub struct AsciiEncoder {
+    new_line: String,
+}
+impl AsciiEncoder {
+    pub fn new(new_line: String) -> Self {
+        AsciiEncoder { new_line: new_line }
+    }
+    fn write_value<V: ToString>(&self, out: &mut Write, value: &V) -> Result<usize> {
+        write_all_bytes(out, value.to_string().as_bytes())
+    }
+    fn write_ascii_list<D: Display>(&self, out: &mut Write, list: &[D]) -> Result<usize> {
+        let mut written = try!(write_all_bytes(out, list.len().to_string().as_bytes()));
         for v in list {
-            written += try!(out_val(out, v));
+            written += try!(write_all_bytes(out, " ".as_bytes()));
+            written += try!(write_all_bytes(out, v.to_string().as_bytes()));
         }
         Ok(written)
     }
-    fn __write_ascii_element<T: Write>(&self, out: &mut T, element: &DefaultElement) -> Result<usize> {
-        let mut written = 0;
-        let mut p_iter = element.iter();
-        let (_name, prop_val) = p_iter.next().unwrap();
-        written += try!(self.write_ascii_property(out, prop_val));
-        loop {
-            written += try!(out.write(" ".as_bytes()));
-            let n = p_iter.next();
-            if n == None {
-                break;
-            }
-            let (_name, prop_val) = n.unwrap();
-            written += try!(self.write_ascii_property(out, prop_val));
+}
+impl ElementEncoder for AsciiEncoder {
+    fn separator(&self, out: &mut Write) -> Result<usize> {
+        write_all_bytes(out, " ".as_bytes())
+    }
+    fn end_element(&self, out: &mut Write) -> Result<usize> {
+        write_all_bytes(out, self.new_line.as_bytes())
+    }
+    fn write_scalar(&self, out: &mut Write, _scalar_type: &ScalarType, property: &Property) -> Result<usize> {
+        match *property {
+            Property::Char(ref v) => self.write_value(out, v),
+            Property::UChar(ref v) => self.write_value(out, v),
+            Property::Short(ref v) => self.write_value(out, v),
+            Property::UShort(ref v) => self.write_value(out, v),
+            Property::Int(ref v) => self.write_value(out, v),
+            Property::UInt(ref v) => self.write_value(out, v),
+            Property::Float(ref v) => self.write_value(out, v),
+            Property::Double(ref v) => self.write_value(out, v),
+            _ => Err(WriteError::Io(io::Error::new(io::ErrorKind::InvalidData, "expected a scalar property, found a list"))),
         }
-        written += try!(self.write_new_line(out));
-        Ok(written)
     }
-    fn write_ascii_property<T: Write>(&self, out: &mut T, data_element: &Property) -> Result<usize> {
-         let result = match *data_element {
-            Property::Char(ref v) => self.write_simple_value(v, out),
-            Property::UChar(ref v) => self.write_simple_value(v, out),
-            Property::Short(ref v) => self.write_simple_value(v, out),
-            Property::UShort(ref v) => self.write_simple_value(v, out),
-            Property::Int(ref v) => self.write_simple_value(v, out),
-            Property::UInt(ref v) => self.write_simple_value(v, out),
-            Property::Float(ref v) => self.write_simple_value(v, out),
-            Property::Double(ref v) => self.write_simple_value(v, out),
-            Property::ListChar(ref v) => self.write_ascii_list(v, out),
-            Property::ListUChar(ref v) => self.write_ascii_list(v, out),
-            Property::ListShort(ref v) => self.write_ascii_list(v, out),
-            Property::ListUShort(ref v) => self.write_ascii_list(v, out),
-            Property::ListInt(ref v) => self.write_ascii_list(v, out),
-            Property::ListUInt(ref v) => self.write_ascii_list(v, out),
-            Property::ListFloat(ref v) => self.write_ascii_list(v, out),
-            Property::ListDouble(ref v) => self.write_ascii_list(v, out),
-        };
-        result
+    fn write_list(&self, out: &mut Write, _index_type: &ScalarType, _content_type: &ScalarType, property: &Property, _element_def: &ElementDef) -> Result<usize> {
+        match *property {
+            Property::ListChar(ref v) => self.write_ascii_list(out, v),
+            Property::ListUChar(ref v) => self.write_ascii_list(out, v),
+            Property::ListShort(ref v) => self.write_ascii_list(out, v),
+            Property::ListUShort(ref v) => self.write_ascii_list(out, v),
+            Property::ListInt(ref v) => self.write_ascii_list(out, v),
+            Property::ListUInt(ref v) => self.write_ascii_list(out, v),
+            Property::ListFloat(ref v) => self.write_ascii_list(out, v),
+            Property::ListDouble(ref v) => self.write_ascii_list(out, v),
+            _ => Err(WriteError::Io(io::Error::new(io::ErrorKind::InvalidData, "expected a list property, found a scalar"))),
+        }
     }
+}
 
-    fn write_new_line<T: Write>(&self, out: &mut T) -> Result<usize> {
-        out.write(self.new_line.as_bytes())
+/// Length of a list `Property`, regardless of its content type.
+fn list_len(property: &Property) -> Result<usize> {
+    match *property {
+        Property::ListChar(ref l) => Ok(l.len()),
+        Property::ListUChar(ref l) => Ok(l.len()),
+        Property::ListShort(ref l) => Ok(l.len()),
+        Property::ListUShort(ref l) => Ok(l.len()),
+        Property::ListInt(ref l) => Ok(l.len()),
+        Property::ListUInt(ref l) => Ok(l.len()),
+        Property::ListFloat(ref l) => Ok(l.len()),
+        Property::ListDouble(ref l) => Ok(l.len()),
+        _ => Err(WriteError::Io(io::Error::new(io::ErrorKind::InvalidData, "expected a list property, found a scalar"))),
     }
-    fn write_simple_value<T: Write, V: ToString>(&self, value: &V, out: &mut T) -> Result<usize> {
-        out.write(value.to_string().as_bytes())
+}
+
+/// Ensures a list length can be represented by the declared index `ScalarType`.
+///
+/// A ragged list longer than the index type can hold (e.g. more than 255 entries
+/// behind a `uchar` index) would silently truncate on write, so reject it up front.
+fn check_index_fits(len: usize, index_type: &ScalarType) -> Result<()> {
+    let max = match *index_type {
+        ScalarType::Char => i8::max_value() as usize,
+        ScalarType::UChar => u8::max_value() as usize,
+        ScalarType::Short => i16::max_value() as usize,
+        ScalarType::UShort => u16::max_value() as usize,
+        ScalarType::Int => i32::max_value() as usize,
+        ScalarType::UInt => u32::max_value() as usize,
+        ScalarType::Float | ScalarType::Double => {
+            return Err(WriteError::InvalidListIndexType);
+        }
+    };
+    if len > max {
+        return Err(WriteError::ListIndexOverflow);
     }
-    fn write_ascii_list<T: Write, D: Clone + Display>(&self, list: &Vec<D>, out: &mut T) -> Result<usize> {
-        self.write_list(list, out, &|o, number| o.write(number.to_string().as_bytes()))
+    Ok(())
+}
+
+/// Emits the fixed-width `binary_big_endian`/`binary_little_endian` payload
+/// layout; the byte order is fixed by the `ByteOrder` type parameter.
+pub struct BinaryEncoder<B: ByteOrder> {
+    phantom: PhantomData<B>,
+}
+impl<B: ByteOrder> BinaryEncoder<B> {
+    pub fn new() -> Self {
+        BinaryEncoder { phantom: PhantomData }
     }
-    fn write_list<T: Write, D: Clone>(&self, list: &[D], out: &mut T, out_val: &Fn(&mut T, &D) -> Result<usize>) -> Result<usize> {
+    fn write_binary_list<D>(&self, out: &mut Write, list: &[D], out_val: &Fn(&mut Write, &D) -> Result<usize>) -> Result<usize> {
         let mut written = 0;
-        written += try!(out.write(&list.len().to_string().as_bytes()));
-        let b = " ".as_bytes();
         for v in list {
-            written += try!(out.write(b));
             written += try!(out_val(out, v));
         }
         Ok(written)
     }
 }
+impl<B: ByteOrder> ElementEncoder for BinaryEncoder<B> {
+    fn write_scalar(&self, out: &mut Write, scalar_type: &ScalarType, property: &Property) -> Result<usize> {
+        match (*scalar_type, property) {
+            (ScalarType::Char, &Property::Char(v)) => { try!(out.write_i8(v)); Ok(1) },
+            (ScalarType::UChar, &Property::UChar(v)) => { try!(out.write_u8(v)); Ok(1) },
+            (ScalarType::Short, &Property::Short(v)) => { try!(out.write_i16::<B>(v)); Ok(2) },
+            (ScalarType::UShort, &Property::UShort(v)) => { try!(out.write_u16::<B>(v)); Ok(2) },
+            (ScalarType::Int, &Property::Int(v)) => { try!(out.write_i32::<B>(v)); Ok(4) },
+            (ScalarType::UInt, &Property::UInt(v)) => { try!(out.write_u32::<B>(v)); Ok(4) },
+            (ScalarType::Float, &Property::Float(v)) => { try!(out.write_f32::<B>(v)); Ok(4) },
+            (ScalarType::Double, &Property::Double(v)) => { try!(out.write_f64::<B>(v)); Ok(8) },
+            _ => Err(WriteError::Io(io::Error::new(io::ErrorKind::InvalidData, "property value does not match its declared scalar type"))),
+        }
+    }
+    fn write_list(&self, out: &mut Write, index_type: &ScalarType, content_type: &ScalarType, property: &Property, _element_def: &ElementDef) -> Result<usize> {
+        // The count prefix is this row's own list length, not the element group's
+        // count: list lengths vary per row (e.g. `vertex_indices`), so using the
+        // group count corrupts every binary list.
+        let vec_len = try!(list_len(property));
+        try!(check_index_fits(vec_len, index_type));
+        let mut written = match *index_type {
+            ScalarType::Char => { try!(out.write_i8(vec_len as i8)); 1 },
+            ScalarType::UChar => { try!(out.write_u8(vec_len as u8)); 1 },
+            ScalarType::Short => { try!(out.write_i16::<B>(vec_len as i16)); 2 },
+            ScalarType::UShort => { try!(out.write_u16::<B>(vec_len as u16)); 2 },
+            ScalarType::Int => { try!(out.write_i32::<B>(vec_len as i32)); 4 },
+            ScalarType::UInt => { try!(out.write_u32::<B>(vec_len as u32)); 4 },
+            ScalarType::Float => return Err(WriteError::InvalidListIndexType),
+            ScalarType::Double => return Err(WriteError::InvalidListIndexType),
+        };
+        written += match (*content_type, property) {
+            (ScalarType::Char, &Property::ListChar(ref l)) => try!(self.write_binary_list(out, l, &|o, x| { try!(o.write_i8(*x)); Ok(1) })),
+            (ScalarType::UChar, &Property::ListUChar(ref l)) => try!(self.write_binary_list(out, l, &|o, x| { try!(o.write_u8(*x)); Ok(1) })),
+            (ScalarType::Short, &Property::ListShort(ref l)) => try!(self.write_binary_list(out, l, &|o, x| { try!(o.write_i16::<B>(*x)); Ok(2) })),
+            (ScalarType::UShort, &Property::ListUShort(ref l)) => try!(self.write_binary_list(out, l, &|o, x| { try!(o.write_u16::<B>(*x)); Ok(2) })),
+            (ScalarType::Int, &Property::ListInt(ref l)) => try!(self.write_binary_list(out, l, &|o, x| { try!(o.write_i32::<B>(*x)); Ok(4) })),
+            (ScalarType::UInt, &Property::ListUInt(ref l)) => try!(self.write_binary_list(out, l, &|o, x| { try!(o.write_u32::<B>(*x)); Ok(4) })),
+            (ScalarType::Float, &Property::ListFloat(ref l)) => try!(self.write_binary_list(out, l, &|o, x| { try!(o.write_f32::<B>(*x)); Ok(4) })),
+            (ScalarType::Double, &Property::ListDouble(ref l)) => try!(self.write_binary_list(out, l, &|o, x| { try!(o.write_f64::<B>(*x)); Ok(8) })),
+            _ => return Err(WriteError::Io(io::Error::new(io::ErrorKind::InvalidData, "list property value does not match its declared content type"))),
+        };
+        Ok(written)
+    }
+}
 use std::fmt::Display;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ply::*;
+    use byteorder::{ BigEndian, ReadBytesExt };
+    use std::io::Cursor;
+
+    /// Builds a one-property `face` element definition carrying a
+    /// `list <index> int` `vertex_indices` property.
+    fn face_def(index_type: ScalarType) -> ElementDef {
+        let mut def = ElementDef::new("face".to_string());
+        def.count = 0;
+        def.properties.add(PropertyDef::new(
+            "vertex_indices".to_string(),
+            PropertyType::List(index_type, ScalarType::Int),
+        ));
+        def
+    }
+
+    fn face_row(indices: Vec<i32>) -> DefaultElement {
+        let mut face = DefaultElement::new();
+        face.insert("vertex_indices".to_string(), Property::ListInt(indices));
+        face
+    }
+
+    /// Ragged rows must each be prefixed with their own list length, not the
+    /// group count, and the values must survive a decode of the emitted bytes.
+    #[test]
+    fn binary_ragged_lists_round_trip() {
+        let def = face_def(ScalarType::UChar);
+        let rows = vec![vec![0, 1, 2], vec![3, 4, 5, 6], vec![7]];
+
+        let encoder = BinaryEncoder::<BigEndian>::new();
+        let mut buf = Vec::new();
+        for indices in &rows {
+            encoder.write_element(&mut buf, &face_row(indices.clone()), &def).unwrap();
+        }
+
+        let mut cursor = Cursor::new(buf);
+        for indices in &rows {
+            let len = cursor.read_u8().unwrap() as usize;
+            assert_eq!(len, indices.len());
+            let mut decoded = Vec::with_capacity(len);
+            for _ in 0..len {
+                decoded.push(cursor.read_i32::<BigEndian>().unwrap());
+            }
+            assert_eq!(&decoded, indices);
+        }
+    }
+
+    /// A list longer than the declared `uchar` index can represent must be
+    /// rejected rather than silently truncated.
+    #[test]
+    fn binary_list_overflowing_uchar_index_is_rejected() {
+        let def = face_def(ScalarType::UChar);
+        let row = face_row((0..300).collect());
+
+        let encoder = BinaryEncoder::<BigEndian>::new();
+        let mut buf = Vec::new();
+        match encoder.write_element(&mut buf, &row, &def) {
+            Err(WriteError::ListIndexOverflow) => (),
+            other => panic!("expected ListIndexOverflow, got {:?}", other),
+        }
+    }
+}